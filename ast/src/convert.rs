@@ -0,0 +1,35 @@
+//! The typing pass: turns the parser's untyped `Term<'a, ()>` into a
+//! fully annotated `Term<'a, Type>`, or a `Diagnostic` describing the
+//! first type error found.
+//!
+//! The actual work -- fresh type variables, constraint collection and
+//! union-find unification -- lives in `infer.rs`; this module just owns
+//! the environment types the rest of the crate already depends on
+//! (`TyEnv`, `FuncSigs`) and the two entry points that kick inference
+//! off.
+
+use std::collections::HashMap;
+
+use diagnostic::Diagnostic;
+use infer;
+use {Program, Term, Type};
+
+/// Maps a variable name in scope to its resolved type.
+pub type TyEnv<'a> = HashMap<&'a str, Type>;
+
+/// Maps a function name to its parameter types and return type, so a
+/// `Call` can resolve to something other than a hardcoded `I32`.
+pub type FuncSigs<'a> = HashMap<&'a str, (&'a [Type], Type)>;
+
+pub fn convert<'a>(term: &'a Term<'a, ()>,
+                    env: &TyEnv<'a>,
+                    sigs: &FuncSigs<'a>) -> Result<&'a Term<'a, Type>, Diagnostic> {
+    infer::infer(term, env, sigs)
+}
+
+/// Converts every function in `program`, first collecting all of their
+/// signatures so that a call to a function defined later in the file
+/// still resolves to its real return type.
+pub fn convert_program<'a>(program: &'a Program<'a, ()>) -> Result<&'a Program<'a, Type>, Diagnostic> {
+    infer::infer_program(program)
+}
@@ -0,0 +1,108 @@
+//! Structured, source-spanned diagnostics, replacing the old
+//! `Result<_, Vec<String>>` / `Result<_, String>` plumbing that gave
+//! errors like "Variable x isn't declared yet." with no location.
+
+use span::Span;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn error<S: Into<String>>(span: Span, message: S) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, message: message.into(), span: span }
+    }
+
+    pub fn warning<S: Into<String>>(span: Span, message: S) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, message: message.into(), span: span }
+    }
+
+    /// Renders `self` against `source`, printing the offending line
+    /// followed by a caret underline under the span:
+    ///
+    /// ```text
+    /// error: Variable x isn't declared yet.
+    ///   --> line 3
+    ///     let y = x + 1;
+    ///             ^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let (line_no, line, column) = locate(source, self.span.start);
+        let underline_len = (self.span.end.saturating_sub(self.span.start)).max(1);
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        format!("{}: {}\n  --> line {}\n    {}\n    {}{}",
+                label,
+                self.message,
+                line_no,
+                line,
+                " ".repeat(column),
+                "^".repeat(underline_len))
+    }
+}
+
+/// Finds the 1-indexed line number, the text of that line, and the
+/// 0-indexed column of `offset` within `source`.
+fn locate(source: &str, offset: usize) -> (usize, &str, usize) {
+    let mut line_start = 0;
+    for (line_no, line) in source.lines().enumerate() {
+        let line_end = line_start + line.len();
+        if offset <= line_end {
+            return (line_no + 1, line, offset - line_start);
+        }
+        // `+ 1` to step over the newline the `lines()` iterator ate.
+        line_start = line_end + 1;
+    }
+    (source.lines().count().max(1), source.lines().last().unwrap_or(""), 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locate_finds_the_line_and_column_an_offset_falls_on() {
+        let source = "let x = 1;\nlet y = x + 1;\n";
+        // "        x" on the second line -- offset of the `x` in `x + 1`.
+        let offset = source.find("x + 1").unwrap();
+        assert_eq!(locate(source, offset), (2, "let y = x + 1;", 8));
+    }
+
+    #[test]
+    fn locate_falls_back_to_the_last_line_past_the_end() {
+        let source = "let x = 1;";
+        let (line_no, line, _) = locate(source, source.len() + 5);
+        assert_eq!((line_no, line), (1, "let x = 1;"));
+    }
+
+    #[test]
+    fn render_underlines_the_full_span() {
+        let source = "let y = x + 1;";
+        let span = Span::new(8, 9); // just the `x`
+        let rendered = Diagnostic::error(span, "Variable x isn't declared yet.").render(source);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], "error: Variable x isn't declared yet.");
+        assert_eq!(lines[1], "  --> line 1");
+        assert_eq!(lines[2], "    let y = x + 1;");
+        // The caret sits under the `x`, which starts at column 8 (the
+        // line is indented 4 spaces to line up under it).
+        assert_eq!(lines[3], format!("    {}^", " ".repeat(8)));
+    }
+
+    #[test]
+    fn render_labels_warnings_differently_from_errors() {
+        let rendered = Diagnostic::warning(Span::new(0, 1), "unused").render("x");
+        assert!(rendered.starts_with("warning: unused\n"));
+    }
+}
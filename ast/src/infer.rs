@@ -0,0 +1,496 @@
+//! Hindley-Milner-style type inference for `Term<'a, ()>`.
+//!
+//! `convert.rs` used to assign each node's type by matching on its
+//! shape directly: a `Var`'s type was whatever its binding's type
+//! happened to be, an `Infix`'s was whichever side converted first, and
+//! so on. That's fine as long as every type is already pinned down by
+//! the time it's read -- which is true for `let`-before-use, but wasn't
+//! checked at all for a `Call`'s arguments against the callee's
+//! parameters. This module replaces that ad hoc matching with real
+//! constraint solving: every node gets a fresh type variable, equality
+//! constraints are collected from how operators, calls and bindings use
+//! it, the constraints are solved by union-find unification, and the
+//! solution is substituted back over the tree.
+//!
+//! There's no `occurs-check`, and none is needed: `bind` only ever
+//! binds a variable to a concrete `Type`, and `Type` has no constructor that can embed another (still-unresolved) type
+//! variable -- `Array`'s element is a concrete `ElemType`, not a nested
+//! `Type` -- so a variable can never occur inside the type it's being
+//! bound to.
+
+use std::collections::HashMap;
+
+use convert::{FuncSigs, TyEnv};
+use diagnostic::Diagnostic;
+use span::Span;
+use {Block, FunctionDef, Program, Statement, Term, Type};
+
+/// A type that may still be an unresolved variable.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum InferTy {
+    Var(u32),
+    Known(Type),
+}
+
+/// `left` and `right` must end up equal; `span` records where that
+/// requirement came from, for diagnostics.
+struct Constraint {
+    left: InferTy,
+    right: InferTy,
+    span: Span,
+}
+
+/// Union-find over the fresh variables handed out during collection.
+/// `parent[v]` is `v` until it's unified with another variable;
+/// `bound[find(v)]` is the concrete type the whole set has
+/// been unified with, once one is known.
+struct Unifier {
+    parent: Vec<u32>,
+    bound: Vec<Option<Type>>,
+}
+
+impl Unifier {
+    fn new() -> Unifier {
+        Unifier { parent: Vec::new(), bound: Vec::new() }
+    }
+
+    fn fresh(&mut self) -> u32 {
+        let var = self.parent.len() as u32;
+        self.parent.push(var);
+        self.bound.push(None);
+        var
+    }
+
+    fn find(&mut self, var: u32) -> u32 {
+        if self.parent[var as usize] != var {
+            let root = self.find(self.parent[var as usize]);
+            self.parent[var as usize] = root;
+            root
+        } else {
+            var
+        }
+    }
+
+    /// Unions the sets of two variables; any known types they carry are
+    /// reconciled by the caller (`unify`), not here. The root
+    /// with the smaller index always survives, so the end result is the
+    /// same no matter what order the constraints were solved in.
+    fn union_vars(&mut self, a: u32, b: u32) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        let (keep, merged) = if a < b { (a, b) } else { (b, a) };
+        self.parent[merged as usize] = keep;
+    }
+
+    fn bind(&mut self, var: u32, ty: Type, span: Span) -> Result<(), Diagnostic> {
+        let root = self.find(var);
+        match self.bound[root as usize] {
+            Some(existing) if existing != ty => Err(Diagnostic::error(span,
+                format!("Can't unify {:?} with {:?}.", existing, ty))),
+            _ => {
+                self.bound[root as usize] = Some(ty);
+                Ok(())
+            }
+        }
+    }
+
+    fn unify(&mut self, left: InferTy, right: InferTy, span: Span) -> Result<(), Diagnostic> {
+        match (left, right) {
+            (InferTy::Known(l), InferTy::Known(r)) => if l == r {
+                Ok(())
+            } else {
+                Err(Diagnostic::error(span, format!("Can't unify {:?} with {:?}.", l, r)))
+            },
+            (InferTy::Var(v), InferTy::Known(ty)) | (InferTy::Known(ty), InferTy::Var(v)) =>
+                self.bind(v, ty, span),
+            (InferTy::Var(a), InferTy::Var(b)) => {
+                let (root_a, root_b) = (self.find(a), self.find(b));
+                let known = (self.bound[root_a as usize], self.bound[root_b as usize]);
+                self.union_vars(a, b);
+                match known {
+                    (Some(ty_a), Some(ty_b)) if ty_a != ty_b =>
+                        Err(Diagnostic::error(span, format!("Can't unify {:?} with {:?}.", ty_a, ty_b))),
+                    (Some(ty), _) | (_, Some(ty)) => self.bind(a, ty, span),
+                    (None, None) => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// The concrete type `var` resolved to, or a diagnostic if nothing
+    /// ever constrained it to one.
+    fn resolve(&mut self, var: u32, span: Span) -> Result<Type, Diagnostic> {
+        let root = self.find(var);
+        self.bound[root as usize].ok_or_else(|| Diagnostic::error(span,
+            String::from("Couldn't infer a type for this expression; it needs an annotation.")))
+    }
+}
+
+/// Maps a name in scope to the `InferTy` it was bound with, so later
+/// reads of it get unified against the very same variable (or, once
+/// solved, the very same concrete type).
+type InferEnv<'a> = HashMap<&'a str, InferTy>;
+
+/// Phase 1: walks `term`, handing out a fresh `InferTy` per node and
+/// recording the equality constraints implied by how it's used.
+fn collect<'a>(term: &'a Term<'a, ()>,
+                env: &InferEnv<'a>,
+                sigs: &FuncSigs<'a>,
+                unifier: &mut Unifier,
+                constraints: &mut Vec<Constraint>) -> Result<&'a Term<'a, InferTy>, Diagnostic> {
+    let span = term.span();
+    let typed = match *term {
+        Term::Literal(i, (), _) => Term::Literal(i, InferTy::Known(Type::I32), span),
+        Term::Var(name, (), _) => {
+            let ty = match env.get(name) {
+                Some(ty) => *ty,
+                None => return Err(Diagnostic::error(span,
+                    String::from("Variable ") + name + " isn't declared yet.")),
+            };
+            Term::Var(name, ty, span)
+        }
+        Term::Infix(left, op, right, (), _) => {
+            let left = try!(collect(left, env, sigs, unifier, constraints));
+            let right = try!(collect(right, env, sigs, unifier, constraints));
+            constraints.push(Constraint { left: left.ty(), right: right.ty(), span: span });
+            Term::Infix(left, op, right, left.ty(), span)
+        }
+        Term::Call(call, args, (), _) => {
+            let mut typed_args = Vec::with_capacity(args.len());
+            for arg in args {
+                typed_args.push(*try!(collect(arg, env, sigs, unifier, constraints)));
+            }
+            let typed_args: &'a [Term<'a, InferTy>] = Box::leak(typed_args.into_boxed_slice());
+            // A call to a known `FunctionDef` gets each argument unified
+            // against the matching parameter type, and the call itself
+            // resolves to the real return type; a call to anything else
+            // (an as-yet-undeclared extern) defaults to `I32`, matching
+            // `decl_funcs`'s fallback.
+            let ret_ty = match sigs.get(call.name) {
+                Some(&(param_tys, ret_ty)) => {
+                    if param_tys.len() != typed_args.len() {
+                        return Err(Diagnostic::error(span,
+                            format!("{} expects {} argument(s), but got {}.",
+                                    call.name, param_tys.len(), typed_args.len())));
+                    }
+                    for (arg, &param_ty) in typed_args.iter().zip(param_tys) {
+                        constraints.push(Constraint {
+                            left: arg.ty(),
+                            right: InferTy::Known(param_ty),
+                            span: arg.span(),
+                        });
+                    }
+                    InferTy::Known(ret_ty)
+                }
+                None => InferTy::Known(Type::I32),
+            };
+            Term::Call(call, typed_args, ret_ty, span)
+        }
+        Term::Scope(block, (), _) => {
+            let block = try!(collect_block(block, env, sigs, unifier, constraints));
+            let ty = block.end.ty();
+            Term::Scope(block, ty, span)
+        }
+        Term::While(cond, block, (), _) => {
+            let cond = try!(collect(cond, env, sigs, unifier, constraints));
+            let block = try!(collect_block(block, env, sigs, unifier, constraints));
+            Term::While(cond, block, InferTy::Known(Type::Unit), span)
+        }
+        Term::If(cond, then_block, else_block, (), _) => {
+            let cond = try!(collect(cond, env, sigs, unifier, constraints));
+            let then_block = try!(collect_block(then_block, env, sigs, unifier, constraints));
+            let else_block = try!(collect_block(else_block, env, sigs, unifier, constraints));
+            constraints.push(Constraint {
+                left: then_block.end.ty(),
+                right: else_block.end.ty(),
+                span: span,
+            });
+            Term::If(cond, then_block, else_block, then_block.end.ty(), span)
+        }
+        Term::NewArray(elem, size, (), _) =>
+            Term::NewArray(elem, size, InferTy::Known(Type::Array(elem, size)), span),
+        Term::Index(array, index, (), _) => {
+            let array = try!(collect(array, env, sigs, unifier, constraints));
+            let index = try!(collect(index, env, sigs, unifier, constraints));
+            constraints.push(Constraint { left: index.ty(), right: InferTy::Known(Type::I32), span: span });
+            // The element type can't be a fresh var unified against
+            // `array`'s type, since `Type::Array` isn't itself a
+            // variable-carrying shape; pin it down directly, and reject
+            // indexing anything already known not to be an array here.
+            let elem_var = unifier.fresh();
+            if let InferTy::Known(Type::Array(elem, _)) = array.ty() {
+                try!(unifier.bind(elem_var, elem.to_type(), span));
+            } else if let InferTy::Known(other) = array.ty() {
+                return Err(Diagnostic::error(span,
+                    format!("Only an array can be indexed, found {:?}.", other)));
+            }
+            Term::Index(array, index, InferTy::Var(elem_var), span)
+        }
+    };
+    Ok(Box::leak(Box::new(typed)))
+}
+
+fn collect_block<'a>(block: &'a Block<'a, ()>,
+                      env: &InferEnv<'a>,
+                      sigs: &FuncSigs<'a>,
+                      unifier: &mut Unifier,
+                      constraints: &mut Vec<Constraint>) -> Result<&'a Block<'a, InferTy>, Diagnostic> {
+    let mut env = env.clone();
+    let mut stmts = Vec::with_capacity(block.stmts.len());
+    for stmt in block.stmts {
+        stmts.push(try!(collect_stmt(stmt, &mut env, sigs, unifier, constraints)));
+    }
+    let stmts: &'a [Statement<'a, InferTy>] = Box::leak(stmts.into_boxed_slice());
+    let end = try!(collect(block.end, &env, sigs, unifier, constraints));
+    Ok(Box::leak(Box::new(Block { stmts: stmts, end: end })))
+}
+
+fn collect_stmt<'a>(stmt: &'a Statement<'a, ()>,
+                     env: &mut InferEnv<'a>,
+                     sigs: &FuncSigs<'a>,
+                     unifier: &mut Unifier,
+                     constraints: &mut Vec<Constraint>) -> Result<Statement<'a, InferTy>, Diagnostic> {
+    Ok(match *stmt {
+        Statement::TermSemicolon(term) =>
+            Statement::TermSemicolon(try!(collect(term, env, sigs, unifier, constraints))),
+        Statement::Let(name, rhs, span) => {
+            let rhs = try!(collect(rhs, env, sigs, unifier, constraints));
+            env.insert(name, rhs.ty());
+            Statement::Let(name, rhs, span)
+        }
+        Statement::LetMut(name, rhs, span) => {
+            let rhs = try!(collect(rhs, env, sigs, unifier, constraints));
+            env.insert(name, rhs.ty());
+            Statement::LetMut(name, rhs, span)
+        }
+        Statement::Mutate(name, rhs, span) => {
+            let rhs = try!(collect(rhs, env, sigs, unifier, constraints));
+            let declared = match env.get(name) {
+                Some(ty) => *ty,
+                None => return Err(Diagnostic::error(span,
+                    String::from("Variable ") + name + " isn't declared yet.")),
+            };
+            constraints.push(Constraint { left: declared, right: rhs.ty(), span: span });
+            Statement::Mutate(name, rhs, span)
+        }
+        Statement::MutateIndex(name, index, rhs, span) => {
+            let index = try!(collect(index, env, sigs, unifier, constraints));
+            constraints.push(Constraint { left: index.ty(), right: InferTy::Known(Type::I32), span: span });
+            let rhs = try!(collect(rhs, env, sigs, unifier, constraints));
+            let declared = match env.get(name) {
+                Some(ty) => *ty,
+                None => return Err(Diagnostic::error(span,
+                    String::from("Variable ") + name + " isn't declared yet.")),
+            };
+            let elem_ty = if let InferTy::Known(Type::Array(elem, _)) = declared {
+                elem.to_type()
+            } else {
+                return Err(Diagnostic::error(span,
+                    String::from("Variable ") + name + " isn't an array."));
+            };
+            constraints.push(Constraint { left: rhs.ty(), right: InferTy::Known(elem_ty), span: span });
+            Statement::MutateIndex(name, index, rhs, span)
+        }
+    })
+}
+
+/// Phase 2: solves every collected constraint, in the (deterministic)
+/// order they were generated in, stopping at the first one that can't
+/// be satisfied.
+fn solve(constraints: &[Constraint], unifier: &mut Unifier) -> Result<(), Diagnostic> {
+    for constraint in constraints {
+        try!(unifier.unify(constraint.left, constraint.right, constraint.span));
+    }
+    Ok(())
+}
+
+/// Looks up the concrete type behind `ty`, resolving it through
+/// `unifier` if it's still a variable.
+fn resolve_ty(ty: InferTy, span: Span, unifier: &mut Unifier) -> Result<Type, Diagnostic> {
+    match ty {
+        InferTy::Known(ty) => Ok(ty),
+        InferTy::Var(var) => unifier.resolve(var, span),
+    }
+}
+
+/// Phase 3: walks the `InferTy`-annotated tree again, substituting each
+/// node's variable for the concrete type `solve` found for it.
+fn substitute<'a>(term: &'a Term<'a, InferTy>,
+                   unifier: &mut Unifier) -> Result<&'a Term<'a, Type>, Diagnostic> {
+    let span = term.span();
+    let typed = match *term {
+        Term::Literal(i, ty, _) => Term::Literal(i, try!(resolve_ty(ty, span, unifier)), span),
+        Term::Var(name, ty, _) => Term::Var(name, try!(resolve_ty(ty, span, unifier)), span),
+        Term::Infix(left, op, right, ty, _) => {
+            let left = try!(substitute(left, unifier));
+            let right = try!(substitute(right, unifier));
+            Term::Infix(left, op, right, try!(resolve_ty(ty, span, unifier)), span)
+        }
+        Term::Call(call, args, ty, _) => {
+            let mut typed_args = Vec::with_capacity(args.len());
+            for arg in args {
+                typed_args.push(*try!(substitute(arg, unifier)));
+            }
+            let typed_args: &'a [Term<'a, Type>] = Box::leak(typed_args.into_boxed_slice());
+            Term::Call(call, typed_args, try!(resolve_ty(ty, span, unifier)), span)
+        }
+        Term::Scope(block, ty, _) => {
+            let block = try!(substitute_block(block, unifier));
+            Term::Scope(block, try!(resolve_ty(ty, span, unifier)), span)
+        }
+        Term::While(cond, block, ty, _) => {
+            let cond = try!(substitute(cond, unifier));
+            let block = try!(substitute_block(block, unifier));
+            Term::While(cond, block, try!(resolve_ty(ty, span, unifier)), span)
+        }
+        Term::If(cond, then_block, else_block, ty, _) => {
+            let cond = try!(substitute(cond, unifier));
+            let then_block = try!(substitute_block(then_block, unifier));
+            let else_block = try!(substitute_block(else_block, unifier));
+            Term::If(cond, then_block, else_block, try!(resolve_ty(ty, span, unifier)), span)
+        }
+        Term::NewArray(elem, size, ty, _) =>
+            Term::NewArray(elem, size, try!(resolve_ty(ty, span, unifier)), span),
+        Term::Index(array, index, ty, _) => {
+            let array = try!(substitute(array, unifier));
+            let index = try!(substitute(index, unifier));
+            Term::Index(array, index, try!(resolve_ty(ty, span, unifier)), span)
+        }
+    };
+    Ok(Box::leak(Box::new(typed)))
+}
+
+fn substitute_block<'a>(block: &'a Block<'a, InferTy>,
+                         unifier: &mut Unifier) -> Result<&'a Block<'a, Type>, Diagnostic> {
+    let mut stmts = Vec::with_capacity(block.stmts.len());
+    for stmt in block.stmts {
+        stmts.push(try!(substitute_stmt(stmt, unifier)));
+    }
+    let stmts: &'a [Statement<'a, Type>] = Box::leak(stmts.into_boxed_slice());
+    let end = try!(substitute(block.end, unifier));
+    Ok(Box::leak(Box::new(Block { stmts: stmts, end: end })))
+}
+
+fn substitute_stmt<'a>(stmt: &'a Statement<'a, InferTy>,
+                        unifier: &mut Unifier) -> Result<Statement<'a, Type>, Diagnostic> {
+    Ok(match *stmt {
+        Statement::TermSemicolon(term) => Statement::TermSemicolon(try!(substitute(term, unifier))),
+        Statement::Let(name, rhs, span) => Statement::Let(name, try!(substitute(rhs, unifier)), span),
+        Statement::LetMut(name, rhs, span) =>
+            Statement::LetMut(name, try!(substitute(rhs, unifier)), span),
+        Statement::Mutate(name, rhs, span) =>
+            Statement::Mutate(name, try!(substitute(rhs, unifier)), span),
+        Statement::MutateIndex(name, index, rhs, span) => Statement::MutateIndex(
+            name, try!(substitute(index, unifier)), try!(substitute(rhs, unifier)), span),
+    })
+}
+
+/// Infers `term`'s type (and that of every subterm), the same way
+/// `convert::convert` used to, but via real constraint collection and
+/// union-find unification rather than matching each node's shape
+/// directly.
+pub fn infer<'a>(term: &'a Term<'a, ()>,
+                  env: &TyEnv<'a>,
+                  sigs: &FuncSigs<'a>) -> Result<&'a Term<'a, Type>, Diagnostic> {
+    let infer_env: InferEnv<'a> = env.iter().map(|(&k, &v)| (k, InferTy::Known(v))).collect();
+    let mut unifier = Unifier::new();
+    let mut constraints = Vec::new();
+    let typed = try!(collect(term, &infer_env, sigs, &mut unifier, &mut constraints));
+    try!(solve(&constraints, &mut unifier));
+    substitute(typed, &mut unifier)
+}
+
+/// Infers every function in `program`, the same way `convert::convert_program` did.
+pub fn infer_program<'a>(program: &'a Program<'a, ()>) -> Result<&'a Program<'a, Type>, Diagnostic> {
+    let mut sigs = FuncSigs::new();
+    for def in program.funcs {
+        let param_tys: Vec<Type> = def.params.iter().map(|param| param.ty).collect();
+        let param_tys: &'a [Type] = Box::leak(param_tys.into_boxed_slice());
+        sigs.insert(def.name, (param_tys, def.ret_ty));
+    }
+
+    let mut typed_funcs = Vec::with_capacity(program.funcs.len());
+    for def in program.funcs {
+        let mut env = TyEnv::new();
+        for param in def.params {
+            env.insert(param.name, param.ty);
+        }
+        let infer_env: InferEnv<'a> = env.iter().map(|(&k, &v)| (k, InferTy::Known(v))).collect();
+        let mut unifier = Unifier::new();
+        let mut constraints = Vec::new();
+        let typed = try!(collect_block(def.body, &infer_env, &sigs, &mut unifier, &mut constraints));
+        try!(solve(&constraints, &mut unifier));
+        let body = try!(substitute_block(typed, &mut unifier));
+        typed_funcs.push(FunctionDef {
+            name: def.name,
+            params: def.params,
+            ret_ty: def.ret_ty,
+            body: body,
+            span: def.span,
+        });
+    }
+    let typed_funcs: &'a [FunctionDef<'a, Type>] = Box::leak(typed_funcs.into_boxed_slice());
+    Ok(Box::leak(Box::new(Program { funcs: typed_funcs })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dummy_span() -> Span {
+        Span::new(0, 0)
+    }
+
+    #[test]
+    fn unify_known_with_known_same_type_succeeds() {
+        let mut unifier = Unifier::new();
+        assert!(unifier.unify(InferTy::Known(Type::I32), InferTy::Known(Type::I32),
+                               dummy_span()).is_ok());
+    }
+
+    #[test]
+    fn unify_known_with_known_different_type_fails() {
+        let mut unifier = Unifier::new();
+        assert!(unifier.unify(InferTy::Known(Type::I32), InferTy::Known(Type::Bool),
+                               dummy_span()).is_err());
+    }
+
+    #[test]
+    fn unify_var_with_known_binds_it() {
+        let mut unifier = Unifier::new();
+        let var = unifier.fresh();
+        assert!(unifier.unify(InferTy::Var(var), InferTy::Known(Type::I32), dummy_span()).is_ok());
+        assert_eq!(unifier.resolve(var, dummy_span()), Ok(Type::I32));
+    }
+
+    #[test]
+    fn unify_two_unbound_vars_resolves_once_either_is_bound_later() {
+        let mut unifier = Unifier::new();
+        let (a, b) = (unifier.fresh(), unifier.fresh());
+        assert!(unifier.unify(InferTy::Var(a), InferTy::Var(b), dummy_span()).is_ok());
+        // Neither side has a concrete type yet.
+        assert!(unifier.resolve(a, dummy_span()).is_err());
+        // Binding one half of the union binds the whole set.
+        assert!(unifier.unify(InferTy::Var(b), InferTy::Known(Type::Bool), dummy_span()).is_ok());
+        assert_eq!(unifier.resolve(a, dummy_span()), Ok(Type::Bool));
+        assert_eq!(unifier.resolve(b, dummy_span()), Ok(Type::Bool));
+    }
+
+    #[test]
+    fn unify_two_vars_already_bound_to_different_types_fails() {
+        let mut unifier = Unifier::new();
+        let (a, b) = (unifier.fresh(), unifier.fresh());
+        assert!(unifier.unify(InferTy::Var(a), InferTy::Known(Type::I32), dummy_span()).is_ok());
+        assert!(unifier.unify(InferTy::Var(b), InferTy::Known(Type::Bool), dummy_span()).is_ok());
+        assert!(unifier.unify(InferTy::Var(a), InferTy::Var(b), dummy_span()).is_err());
+    }
+
+    #[test]
+    fn resolve_an_unconstrained_var_is_an_error() {
+        let mut unifier = Unifier::new();
+        let var = unifier.fresh();
+        assert!(unifier.resolve(var, dummy_span()).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! AST types shared between the frontend parser and the LLVM backend.
+//!
+//! Nodes are allocated in an arena and threaded through with the `'a`
+//! lifetime, so every type here is a plain `Copy` bag of references
+//! rather than an owning tree. The `T` parameter carries a per-node type
+//! annotation: the parser produces `Term<'a, ()>` ("untyped"), and
+//! `convert::convert` (see `convert.rs`) resolves it into a fully
+//! annotated `Term<'a, Type>`. Every node also carries a `Span` so
+//! errors (see `diagnostic.rs`) can point back at the source text.
+//!
+//! There's no shared arena type backing that lifetime -- `infer` (which
+//! `convert` delegates to) allocates its output nodes with `Box::leak`, a
+//! deliberate choice rather than an oversight: this crate only ever
+//! runs once per compiler invocation, so leaking for the rest of the
+//! process's lifetime is indistinguishable from arena allocation in
+//! practice, without needing an arena crate or a lifetime threaded
+//! through every function signature. This stops being fine the moment
+//! anything here runs in a loop within one process (a language server,
+//! a REPL, a test harness compiling many programs) -- at that point it
+//! needs a real arena.
+
+pub mod convert;
+pub mod diagnostic;
+pub mod infer;
+pub mod span;
+
+pub use span::Span;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// A resolved, monomorphic type. Fixed-size arrays hold a scalar
+/// `ElemType` and a compile-time-known length; grows to cover structs
+/// (and arrays of arrays) once the typing pass needs them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Type {
+    I32,
+    Bool,
+    Unit,
+    Array(ElemType, u32),
+}
+
+/// The element type of an `Array`. Kept separate from `Type` (rather
+/// than letting `Array` hold a boxed `Type`) so `Type` stays `Copy`
+/// without a lifetime of its own; widen this enum, not `Type` itself,
+/// when arrays need to nest.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ElemType {
+    I32,
+    Bool,
+}
+
+impl ElemType {
+    pub fn to_type(self) -> Type {
+        match self {
+            ElemType::I32 => Type::I32,
+            ElemType::Bool => Type::Bool,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct FunctionCall<'a> {
+    pub name: &'a str,
+    pub arity: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Term<'a, T = ()> {
+    Literal(i32, T, Span),
+    Var(&'a str, T, Span),
+    Infix(&'a Term<'a, T>, Operator, &'a Term<'a, T>, T, Span),
+    Call(&'a FunctionCall<'a>, &'a [Term<'a, T>], T, Span),
+    Scope(&'a Block<'a, T>, T, Span),
+    While(&'a Term<'a, T>, &'a Block<'a, T>, T, Span),
+    /// `if cond { then_block } else { else_block }`, evaluating to the
+    /// value of whichever block's tail expression ran.
+    If(&'a Term<'a, T>, &'a Block<'a, T>, &'a Block<'a, T>, T, Span),
+    /// A fresh fixed-size array whose elements are `undef` until written,
+    /// e.g. the right-hand
+    /// side of `let mut xs = [I32; 10];`.
+    NewArray(ElemType, u32, T, Span),
+    /// `array[index]`.
+    Index(&'a Term<'a, T>, &'a Term<'a, T>, T, Span),
+}
+
+impl<'a, T: Copy> Term<'a, T> {
+    /// The annotation carried by this node, whatever `T` happens to be.
+    pub fn ty(&self) -> T {
+        use Term::*;
+        match *self {
+            Literal(_, ty, _) | Var(_, ty, _) | Infix(_, _, _, ty, _) | Call(_, _, ty, _)
+            | Scope(_, ty, _) | While(_, _, ty, _) | If(_, _, _, ty, _)
+            | NewArray(_, _, ty, _) | Index(_, _, ty, _) => ty,
+        }
+    }
+
+    /// The source span this node was parsed from.
+    pub fn span(&self) -> Span {
+        use Term::*;
+        match *self {
+            Literal(_, _, span) | Var(_, _, span) | Infix(_, _, _, _, span)
+            | Call(_, _, _, span) | Scope(_, _, span) | While(_, _, _, span)
+            | If(_, _, _, _, span) | NewArray(_, _, _, span) | Index(_, _, _, span) => span,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Statement<'a, T = ()> {
+    TermSemicolon(&'a Term<'a, T>),
+    Let(&'a str, &'a Term<'a, T>, Span),
+    LetMut(&'a str, &'a Term<'a, T>, Span),
+    Mutate(&'a str, &'a Term<'a, T>, Span),
+    /// `name[index] = rhs;`, mutating a single element of an array
+    /// bound with `LetMut`.
+    MutateIndex(&'a str, &'a Term<'a, T>, &'a Term<'a, T>, Span),
+}
+
+impl<'a, T: Copy> Statement<'a, T> {
+    /// The source span of the statement itself (for `TermSemicolon`,
+    /// simply the span of the wrapped term).
+    pub fn span(&self) -> Span {
+        use Statement::*;
+        match *self {
+            TermSemicolon(term) => term.span(),
+            Let(_, _, span) | LetMut(_, _, span) | Mutate(_, _, span)
+            | MutateIndex(_, _, _, span) => span,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Block<'a, T = ()> {
+    pub stmts: &'a [Statement<'a, T>],
+    pub end: &'a Term<'a, T>,
+}
+
+/// A typed function parameter, e.g. `x: I32`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Param<'a> {
+    pub name: &'a str,
+    pub ty: Type,
+}
+
+/// A top-level function definition: `fn name(params) -> ret_ty { body }`.
+#[derive(Copy, Clone, Debug)]
+pub struct FunctionDef<'a, T = ()> {
+    pub name: &'a str,
+    pub params: &'a [Param<'a>],
+    pub ret_ty: Type,
+    pub body: &'a Block<'a, T>,
+    pub span: Span,
+}
+
+/// The whole compilation unit: every function it defines.
+#[derive(Copy, Clone, Debug)]
+pub struct Program<'a, T = ()> {
+    pub funcs: &'a [FunctionDef<'a, T>],
+}
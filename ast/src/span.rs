@@ -0,0 +1,21 @@
+//! Byte-offset source spans, attached to every AST node so diagnostics
+//! can point back at the offending source text.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start: start, end: end }
+    }
+
+    /// A span covering both `self` and `other`, for nodes built out of
+    /// several sub-spans (e.g. an `if` spanning its condition through
+    /// its `else` block).
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start, end: other.end }
+    }
+}
@@ -1,71 +1,88 @@
-use std::os::raw::c_char;
 use std::collections::{HashSet, HashMap};
 
+use llvm_sys::core::{LLVMArrayType, LLVMFunctionType, LLVMPointerType};
 use llvm_sys::prelude::*;
-use llvm_sys::core::*;
+use llvm_sys::LLVMIntPredicate::{LLVMIntEQ, LLVMIntNE};
 
 use ast::*;
+use ast::convert::TyEnv;
+use ast::diagnostic::Diagnostic;
+
+use llvm::{self, Builder, Module};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Levity {
     Boxed,
-    Unboxed(i32),
-}
-
-trait ToRaw: Into<Vec<u8>> {
-    fn to_raw(self) -> Result<*const c_char, Vec<String>>;
+    Unboxed,
 }
 
-impl<'a> ToRaw for &'a str {
-    fn to_raw(self: &'a str) -> Result<*const c_char, Vec<String>> {
-        use std::error::Error;
-        use std::ffi::CString;
-        CString::new(self).map(|str| str.as_ptr() as *const i8)
-                          .map_err(|err| vec![err.description().to_string()])
+/// The LLVM type backing a resolved `ast::Type`.
+fn llvm_type(ty: Type) -> LLVMTypeRef {
+    use llvm_sys::core::{LLVMInt32Type, LLVMInt1Type, LLVMVoidType};
+    unsafe {
+        match ty {
+            Type::I32 => LLVMInt32Type(),
+            Type::Bool => LLVMInt1Type(),
+            Type::Unit => LLVMVoidType(),
+            Type::Array(elem, size) => LLVMArrayType(llvm_type(elem.to_type()), size),
+        }
     }
 }
 
 pub type Map<'a> = HashMap<&'a str, (LLVMValueRef, Levity)>;
 
-impl<'a> Term<'a> {
-    pub fn rhs_vars(self: &'a Term<'a>) -> HashSet<&'a str> {
+impl<'a> Term<'a, Type> {
+    pub fn rhs_vars(self: &'a Term<'a, Type>) -> HashSet<&'a str> {
         use ast::Term::*;
         match *self {
-            Literal(_) => HashSet::new(),
-            Var(name) => {
+            Literal(_, _, _) => HashSet::new(),
+            Var(name, _, _) => {
                 let mut set = HashSet::new();
                 set.insert(name);
                 set
             }
-            Infix(ref left, _, ref right) => left.rhs_vars()
+            Infix(ref left, _, ref right, _, _) => left.rhs_vars()
                                                  .union(&right.rhs_vars())
                                                  .cloned()
                                                  .collect(),
-            Call(_, args) =>
+            Call(_, args, _, _) =>
                 args.iter()
                     .map(|arg| arg.rhs_vars())
                     .fold(HashSet::new(), |l, r| l.union(&r).cloned().collect()),
-            Scope(ref block) => block.rhs_vars(),
-            While(ref cond, ref block) =>
+            Scope(ref block, _, _) => block.rhs_vars(),
+            While(ref cond, ref block, _, _) =>
                 cond.rhs_vars().union(&block.rhs_vars()).cloned().collect(),
+            If(ref cond, ref then_block, ref else_block, _, _) =>
+                cond.rhs_vars()
+                    .union(&then_block.rhs_vars())
+                    .cloned()
+                    .collect::<HashSet<&str>>()
+                    .union(&else_block.rhs_vars())
+                    .cloned()
+                    .collect(),
+            NewArray(_, _, _, _) => HashSet::new(),
+            Index(ref array, ref index, _, _) =>
+                array.rhs_vars().union(&index.rhs_vars()).cloned().collect(),
         }
     }
 }
 
-impl<'a> Statement<'a> {
-    pub fn rhs_vars(self: &'a Statement<'a>,) -> HashSet<&str> {
+impl<'a> Statement<'a, Type> {
+    pub fn rhs_vars(self: &'a Statement<'a, Type>,) -> HashSet<&str> {
         use ast::Statement::*;
         match *self {
             TermSemicolon(ref term) => term.rhs_vars(),
-            Let(_, ref rhs) => rhs.rhs_vars(),
-            LetMut(_, ref rhs) => rhs.rhs_vars(),
-            Mutate(_, ref rhs) => rhs.rhs_vars(),
+            Let(_, ref rhs, _) => rhs.rhs_vars(),
+            LetMut(_, ref rhs, _) => rhs.rhs_vars(),
+            Mutate(_, ref rhs, _) => rhs.rhs_vars(),
+            MutateIndex(_, ref index, ref rhs, _) =>
+                index.rhs_vars().union(&rhs.rhs_vars()).cloned().collect(),
         }
     }
 }
 
-impl<'a> Block<'a> {
-    pub fn rhs_vars(self: &'a Block<'a>) -> HashSet<&str> {
+impl<'a> Block<'a, Type> {
+    pub fn rhs_vars(self: &'a Block<'a, Type>) -> HashSet<&str> {
         let stmts_rhs_vars = self.stmts
                                  .iter()
                                  .map(|stmt| stmt.rhs_vars())
@@ -75,17 +92,17 @@ impl<'a> Block<'a> {
 }
 
 pub trait FuncsDecl<'a> {
-    fn find_funcs(self: &'a Self) -> Result<HashSet<&'a FunctionCall<'a>>, String>;
-    unsafe fn decl_funcs(self: &'a Self, module: LLVMModuleRef) -> Result<(), String> {
+    fn find_funcs(self: &'a Self) -> Result<HashSet<&'a FunctionCall<'a>>, Diagnostic>;
+    fn decl_funcs(self: &'a Self, module: &mut Module) -> Result<(), Diagnostic> {
         let funcs = try!(self.find_funcs());
         for func in funcs {
-            let ret_ty = LLVMInt32Type();
-            let args_ty = (&mut *vec![LLVMInt32Type(); func.arity as usize]).as_mut_ptr();
-            let func_ty = LLVMFunctionType(ret_ty, args_ty, func.arity, 0);
-            LLVMAddFunction(
-                // Actually unnessasary clone.
-                module, try!(func.name.to_raw().map_err(|err| err[0].clone())), func_ty
-            );
+            // Every call site found here is declared `I32`;
+            // `decl_program_funcs` declares each `FunctionDef` with its
+            // real argument and return types.
+            let ret_ty = llvm_type(Type::I32);
+            let args_ty = (&mut *vec![llvm_type(Type::I32); func.arity as usize]).as_mut_ptr();
+            let func_ty = unsafe { LLVMFunctionType(ret_ty, args_ty, func.arity, 0) };
+            module.add_function(func.name, func_ty);
         }
         Ok(())
     }
@@ -97,121 +114,193 @@ pub trait Compile<'a>: FuncsDecl<'a> {
 
     fn new_env() -> Self::Env;
 
-    unsafe fn build(self: &'a Self,
-                    module: LLVMModuleRef,
+    fn build(self: &'a Self,
+             module: &mut Module,
+             func: LLVMValueRef,
+             entry: LLVMBasicBlockRef,
+             builder: &mut Builder,
+             env: Self::Env) -> Result<LLVMValueRef, Vec<Diagnostic>>;
+
+    fn init_module(self: &'a Self,
+                    module: &mut Module,
                     func: LLVMValueRef,
-                    entry: LLVMBasicBlockRef,
-                    builder: LLVMBuilderRef,
-                    env: Self::Env) -> Result<LLVMValueRef, Vec<String>>;
-
-    unsafe fn init_module(self: &'a Self,
-                          module: LLVMModuleRef,
-                          func: LLVMValueRef,
-                          builder: LLVMBuilderRef) -> Result<(), Vec<String>> {
-        let entry = LLVMAppendBasicBlock(func, "entry\0".as_ptr() as *const i8);
-        LLVMPositionBuilderAtEnd(builder, entry);
+                    builder: &mut Builder) -> Result<(), Vec<Diagnostic>> {
+        let entry = builder.append_basic_block(func, "entry");
+        builder.position_at_end(entry);
         match self.build(module, func, entry, builder, Self::new_env()) {
             Ok(val) => {
-                LLVMBuildRet(builder, val);
+                builder.build_ret(val);
                 Ok(())
             }
             Err(vec) => Err(vec),
         }
     }
 
-    unsafe fn gen_module(self: &'a Self) -> Result<LLVMModuleRef, Vec<String>> {
-        let name = try!("Main".to_raw());
-        let module = LLVMModuleCreateWithName(name);
-        let args: &mut [LLVMTypeRef] = &mut [];
-        let func_ty = LLVMFunctionType(LLVMInt32Type(), args.as_mut_ptr() , 0, 0);
-        let func = LLVMAddFunction(module, try!("main".to_raw()), func_ty);
-        let builder = LLVMCreateBuilder();
-        try!(self.decl_funcs(module).map_err(|err| vec![err]));
-        try!(self.init_module(module, func, builder));
+    fn gen_module(self: &'a Self) -> Result<Module, Vec<Diagnostic>> {
+        let mut module = llvm::create_module("Main");
+        let no_args: &mut [LLVMTypeRef] = &mut [];
+        let func_ty = unsafe { LLVMFunctionType(llvm_type(Type::I32), no_args.as_mut_ptr(), 0, 0) };
+        let func = module.add_function("main", func_ty);
+        let mut builder = Builder::new();
+        try!(self.decl_funcs(&mut module).map_err(|err| vec![err]));
+        try!(self.init_module(&mut module, func, &mut builder));
         Ok(module)
     }
 
 }
 
-impl<'a> FuncsDecl<'a> for Term<'a> {
-    fn find_funcs(self: &'a Term<'a>) -> Result<HashSet<&'a FunctionCall<'a>>, String> {
+/// Runs the typing pass on a parsed `Term<'a, ()>`, handing back the
+/// `Term<'a, Type>` that the rest of this module operates on.
+pub fn typecheck<'a>(term: &'a Term<'a, ()>) -> Result<&'a Term<'a, Type>, Vec<Diagnostic>> {
+    let sigs = ast::convert::FuncSigs::new();
+    ast::convert::convert(term, &TyEnv::new(), &sigs).map_err(|err| vec![err])
+}
+
+/// Declares every `FunctionDef` in `program` with its real signature,
+/// plus an `I32`-returning extern for any call that isn't locally
+/// defined (the old universal-`I32` behaviour from `decl_funcs`, kept
+/// for calls into functions the program never defines, e.g. externs).
+fn decl_program_funcs<'a>(program: &'a Program<'a, Type>,
+                          module: &mut Module) -> Result<(), Diagnostic> {
+    let mut defined = HashSet::new();
+    for def in program.funcs {
+        defined.insert(def.name);
+        let ret_ty = llvm_type(def.ret_ty);
+        let mut param_tys: Vec<LLVMTypeRef> =
+            def.params.iter().map(|param| llvm_type(param.ty)).collect();
+        let func_ty = unsafe {
+            LLVMFunctionType(ret_ty, param_tys.as_mut_ptr(), param_tys.len() as u32, 0)
+        };
+        module.add_function(def.name, func_ty);
+    }
+
+    let mut called = HashSet::new();
+    for def in program.funcs {
+        called = called.union(&try!(def.body.find_funcs())).cloned().collect();
+    }
+    for call in called {
+        if defined.contains(call.name) {
+            continue;
+        }
+        let ret_ty = llvm_type(Type::I32);
+        let args_ty = (&mut *vec![llvm_type(Type::I32); call.arity as usize]).as_mut_ptr();
+        let func_ty = unsafe { LLVMFunctionType(ret_ty, args_ty, call.arity, 0) };
+        module.add_function(call.name, func_ty);
+    }
+    Ok(())
+}
+
+/// Builds the body of every `FunctionDef`, binding its parameters into
+/// a fresh environment the same way a `Let` binding is bound.
+fn define_program_funcs<'a>(program: &'a Program<'a, Type>,
+                            module: &mut Module,
+                            builder: &mut Builder) -> Result<(), Vec<Diagnostic>> {
+    for def in program.funcs {
+        let func = module.get_named_function(def.name);
+        let entry = builder.append_basic_block(func, "entry");
+        builder.position_at_end(entry);
+        let mut env = Map::new();
+        for (index, param) in def.params.iter().enumerate() {
+            let value = llvm::get_param(func, index as u32);
+            env.insert(param.name, (value, Levity::Unboxed));
+        }
+        let result = try!(def.body.build(module, func, entry, builder, Box::new(env)));
+        builder.build_ret(result);
+    }
+    Ok(())
+}
+
+/// Compiles a whole `Program` (as opposed to `Compile::gen_module`'s
+/// single implicit `main` expression) into a fresh `Module`.
+pub fn gen_program<'a>(program: &'a Program<'a, Type>) -> Result<Module, Vec<Diagnostic>> {
+    let mut module = llvm::create_module("Main");
+    try!(decl_program_funcs(program, &mut module).map_err(|err| vec![err]));
+    let mut builder = Builder::new();
+    try!(define_program_funcs(program, &mut module, &mut builder));
+    Ok(module)
+}
+
+impl<'a> FuncsDecl<'a> for Term<'a, Type> {
+    fn find_funcs(self: &'a Term<'a, Type>) -> Result<HashSet<&'a FunctionCall<'a>>, Diagnostic> {
         use ast::Term::*;
         match *self {
-            Literal(_) | Var(_) => Ok(HashSet::new()),
-            Infix(ref left, _, ref right) => {
+            Literal(_, _, _) | Var(_, _, _) => Ok(HashSet::new()),
+            Infix(ref left, _, ref right, _, _) => {
                 Ok(try!(left.find_funcs()).union(&try!(right.find_funcs())).cloned().collect())
             }
-            Call(ref call, ref args) => {
+            Call(ref call, ref args, _, span) => {
+                // Collect the args' own calls first, so a conflicting
+                // arity can actually be checked against something --
+                // checking against a `func_calls` still empty at this
+                // point (as this used to) could never find a conflict.
                 let mut func_calls = HashSet::new();
-                let bool =
-                    func_calls.iter().any(|old_call: &&FunctionCall| old_call.name == call.name);
-                if bool {
-                    return Err(call.name.to_string() + " is called with different parameters.")
-                }
-                func_calls.insert(call);
                 for arg in *args {
                     func_calls = func_calls.union(&try!(arg.find_funcs())).cloned().collect();
                 }
+                let conflicts = func_calls.iter().any(|old_call: &&FunctionCall|
+                    old_call.name == call.name && old_call.arity != call.arity);
+                if conflicts {
+                    return Err(Diagnostic::error(span,
+                        call.name.to_string() + " is called with different parameters."))
+                }
+                func_calls.insert(call);
                 Ok(func_calls)
             }
-            Scope(ref block) => {
+            Scope(ref block, _, _) => {
                 Ok(try!(block.find_funcs()))
             },
-            While(ref cond, ref block) => {
+            While(ref cond, ref block, _, _) => {
                 Ok(try!(cond.find_funcs()).union(&try!(block.find_funcs())).cloned().collect())
             },
+            If(ref cond, ref then_block, ref else_block, _, _) => {
+                let cond_and_then: HashSet<&FunctionCall> =
+                    try!(cond.find_funcs()).union(&try!(then_block.find_funcs())).cloned().collect();
+                Ok(cond_and_then.union(&try!(else_block.find_funcs())).cloned().collect())
+            },
+            NewArray(_, _, _, _) => Ok(HashSet::new()),
+            Index(ref array, ref index, _, _) => {
+                Ok(try!(array.find_funcs()).union(&try!(index.find_funcs())).cloned().collect())
+            }
         }
     }
 }
 
-impl<'a> Compile<'a> for Term<'a> {
+impl<'a> Compile<'a> for Term<'a, Type> {
 
     type Env = Map<'a>;
 
     fn new_env() -> Self::Env { Map::new() }
 
-    unsafe fn build(self: &'a Term<'a>,
-                    module: LLVMModuleRef,
-                    func: LLVMValueRef,
-                    entry: LLVMBasicBlockRef,
-                    builder: LLVMBuilderRef,
-                    env: Self::Env) -> Result<LLVMValueRef, Vec<String>> {
+    fn build(self: &'a Term<'a, Type>,
+             module: &mut Module,
+             func: LLVMValueRef,
+             entry: LLVMBasicBlockRef,
+             builder: &mut Builder,
+             env: Self::Env) -> Result<LLVMValueRef, Vec<Diagnostic>> {
         use ast::Term::*;
-        // Build the instructions.
         match *self {
-            Literal(i) => Ok(LLVMConstInt(LLVMIntType(32), i as u64, 0)),
-            Infix(ref left, ref op, ref right) => {
+            Literal(i, ty, _) => Ok(llvm::const_int(llvm_type(ty), i as u64)),
+            Infix(ref left, ref op, ref right, _, _) => {
                 use ast::Operator::*;
                 let another_env = env.clone();
                 let left = try!(left.build(module, func, entry, builder, env));
                 let right = try!(right.build(module, func, entry, builder, another_env));
-                match *op {
-                    Add => Ok(LLVMBuildAdd(
-                        builder, left, right, try!("add".to_raw())
-                    )),
-                    Sub => Ok(LLVMBuildSub(
-                        builder, left, right, try!("sub".to_raw())
-                    )),
-                    Mul => Ok(LLVMBuildMul(
-                        builder, left, right, try!("mul".to_raw())
-                    )),
-                    Div => Ok(LLVMBuildSDiv(
-                        builder, left, right, try!("div".to_raw())
-                    )),
-                }
+                Ok(match *op {
+                    Add => builder.build_add(left, right, "add"),
+                    Sub => builder.build_sub(left, right, "sub"),
+                    Mul => builder.build_mul(left, right, "mul"),
+                    Div => builder.build_sdiv(left, right, "div"),
+                })
             }
-            Call(ref func_call, ref args) => {
-                let llvm_func =
-                    LLVMGetNamedFunction(module,
-                        try!(func_call.name.to_raw())
-                    );
-                let results: Vec<Result<LLVMValueRef, Vec<String>>> =
+            Call(ref func_call, ref args, _, _) => {
+                let llvm_func = module.get_named_function(func_call.name);
+                let results: Vec<Result<LLVMValueRef, Vec<Diagnostic>>> =
                     args.iter()
                         .map(|term| term.build(module, func, entry, builder, env.clone()))
                         .collect();
                 // It's really so painful.
                 // Read the types of `results` and `result_args` to know what I'm doing.
-                let result_args: Result<Vec<LLVMValueRef>, Vec<String>> =
+                let result_args: Result<Vec<LLVMValueRef>, Vec<Diagnostic>> =
                     results.iter()
                            .fold(Ok(Vec::new()),
                                  |left, right| {
@@ -233,55 +322,46 @@ impl<'a> Compile<'a> for Term<'a> {
                                          }
                                      }
                                  });
-                // let mut raw_args = try!(result_args).as_mut_ptr();
-                // The above line makes the program segfault. Wierd.
                 let mut args: Vec<LLVMValueRef> = try!(result_args);
-                let raw_args = args.as_mut_ptr();
-                let name = &*("call".to_string() + func_call.name);
-                let value = LLVMBuildCall(builder,
-                                          llvm_func,
-                                          raw_args,
-                                          func_call.arity,
-                                          try!(name.to_raw())
-                                         );
-                Ok(value)
+                let name = "call".to_string() + func_call.name;
+                Ok(builder.build_call(llvm_func, &mut args, &name))
             }
-            Var(ref str) => {
+            Var(ref str, ty, span) => {
                 match env.get(str) {
                     Some(pair) => {
                         use self::Levity::*;
                         match pair.1 {
-                            Boxed => Ok(LLVMBuildLoad(
-                                builder, pair.0, try!("load".to_raw())
-                            )),
-                            Unboxed(_) => Ok(pair.0),
+                            // An array lives behind its alloca; indexing
+                            // or mutating it needs the address itself,
+                            // not a load of the whole array's contents.
+                            Boxed => match ty {
+                                Type::Array(_, _) => Ok(pair.0),
+                                _ => Ok(builder.build_load(pair.0, "load")),
+                            },
+                            Unboxed => Ok(pair.0),
                         }
                     }
-                    None => Err(vec![String::from("Variable ") + str + " isn't declared yet."]),
+                    None => Err(vec![Diagnostic::error(span,
+                        String::from("Variable ") + str + " isn't declared yet.")]),
                 }
             }
-            Scope(ref block) => {
+            Scope(ref block, _, _) => {
                 let new_env = env.clone();
-                let block_result = block.build(module, func, entry, builder, Box::new(new_env));
-                let block = try!(block_result);
-                Ok(block)
+                block.build(module, func, entry, builder, Box::new(new_env))
             }
-            While(ref cond, ref block) => {
+            While(ref cond, ref block, _, _) => {
                 // Build the condition.
                 // It has to be done first because it could mutate variables.
                 let built_cond = try!(cond.build(module, func, entry, builder, env.clone()));
                 // And check if the condition equals to zero.
-                let zero = LLVMConstInt(LLVMIntType(32), 0, 0);
-                use llvm_sys::LLVMIntPredicate::LLVMIntEQ;
-                let is_zero = LLVMBuildICmp(
-                    builder, LLVMIntEQ, built_cond, zero, try!("iszero".to_raw())
-                );
+                let zero = llvm::const_int(llvm_type(Type::I32), 0);
+                let is_zero = builder.build_icmp(LLVMIntEQ, built_cond, zero, "iszero");
                 // Create the basic blocks.
-                let loop_block = LLVMAppendBasicBlock(func, try!("loop".to_raw()));
-                let after_loop = LLVMAppendBasicBlock(func, try!("afterloop".to_raw()));
-                LLVMBuildCondBr(builder, is_zero, after_loop, loop_block);
+                let loop_block = builder.append_basic_block(func, "loop");
+                let after_loop = builder.append_basic_block(func, "afterloop");
+                builder.build_cond_br(is_zero, after_loop, loop_block);
                 // Now go inside the loop.
-                LLVMPositionBuilderAtEnd(builder, loop_block);
+                builder.position_at_end(loop_block);
                 // Create a new environment.
                 let mut new_env = env.clone();
                 // Build the phi nodes.
@@ -290,24 +370,16 @@ impl<'a> Compile<'a> for Term<'a> {
                         use self::Levity::*;
                         match pair.1 {
                             Boxed => {
-                                let ty = LLVMPointerType(LLVMIntType(32), 0);
-                                let phi = LLVMBuildPhi(builder, ty, key.as_ptr() as *const i8);
+                                let ty = unsafe { LLVMPointerType(llvm_type(Type::I32), 0) };
+                                let phi = builder.build_phi(ty, key);
                                 let old_ptr = (&env.get(key)).unwrap().0;
-                                LLVMAddIncoming(phi,
-                                                [old_ptr, phi].as_mut_ptr(),
-                                                [entry, loop_block].as_mut_ptr(),
-                                                2);
+                                builder.add_incoming(phi, &mut [old_ptr, phi], &mut [entry, loop_block]);
                                 new_env.insert(key, (phi, Boxed));
                             }
-                            Unboxed(_) => {
-                                let name = try!((*key).to_raw());
-                                let phi =
-                                    LLVMBuildPhi(builder, LLVMIntType(32), name);
+                            Unboxed => {
+                                let phi = builder.build_phi(llvm_type(Type::I32), key);
                                 let pair = *new_env.get(key).unwrap(); // Safe here.
-                                LLVMAddIncoming(phi,
-                                                [pair.0, phi].as_mut_ptr(),
-                                                [entry, loop_block].as_mut_ptr(),
-                                                2);
+                                builder.add_incoming(phi, &mut [pair.0, phi], &mut [entry, loop_block]);
                                 // Update the enviroment.
                                 new_env.insert(key, (phi, pair.1));
                             }
@@ -317,32 +389,98 @@ impl<'a> Compile<'a> for Term<'a> {
                 try!(block.build(module, func, entry, builder, Box::new(new_env.clone())));
                 // Check the condition for next iteration.
                 let built_cond = try!(cond.build(module, func, entry, builder, new_env));
-                let is_zero = LLVMBuildICmp(
-                    builder, LLVMIntEQ, built_cond, zero, try!("iszero".to_raw())
-                );
-                LLVMBuildCondBr(builder, is_zero, after_loop, loop_block);
-                LLVMPositionBuilderAtEnd(builder, after_loop);
+                let is_zero = builder.build_icmp(LLVMIntEQ, built_cond, zero, "iszero");
+                builder.build_cond_br(is_zero, after_loop, loop_block);
+                builder.position_at_end(after_loop);
                 Ok(zero)
             }
+            If(ref cond, ref then_block, ref else_block, ty, _) => {
+                // Evaluate the condition; anything non-zero is truthy.
+                let built_cond = try!(cond.build(module, func, entry, builder, env.clone()));
+                let zero = llvm::const_int(llvm_type(Type::I32), 0);
+                let is_true = builder.build_icmp(LLVMIntNE, built_cond, zero, "ifcond");
+                let then_bb = builder.append_basic_block(func, "then");
+                let else_bb = builder.append_basic_block(func, "else");
+                let merge_bb = builder.append_basic_block(func, "ifcont");
+                builder.build_cond_br(is_true, then_bb, else_bb);
+
+                builder.position_at_end(then_bb);
+                let then_val =
+                    try!(then_block.build(module, func, entry, builder, Box::new(env.clone())));
+                let then_end_bb = builder.insert_block();
+                // A branch that already terminated itself (an early
+                // `ret`/`br` -- nothing in this language emits one yet,
+                // but a `build` impl down the line might) neither needs
+                // another `br` to the merge block nor feeds the phi.
+                let then_live = !builder.block_terminated(then_end_bb);
+                if then_live {
+                    builder.build_br(merge_bb);
+                }
+
+                builder.position_at_end(else_bb);
+                let else_val =
+                    try!(else_block.build(module, func, entry, builder, Box::new(env.clone())));
+                let else_end_bb = builder.insert_block();
+                let else_live = !builder.block_terminated(else_end_bb);
+                if else_live {
+                    builder.build_br(merge_bb);
+                }
+
+                builder.position_at_end(merge_bb);
+                match ty {
+                    // A `Unit`-typed `if` (e.g. each branch's tail is a
+                    // `while`) has nothing to phi -- `llvm_type(Unit)`
+                    // is `void`, and a phi of `void` is invalid IR. Use
+                    // the same zero sentinel `While` already returns for
+                    // its own `Unit` value.
+                    Type::Unit => Ok(zero),
+                    _ => {
+                        let mut incoming_vals = Vec::new();
+                        let mut incoming_blocks = Vec::new();
+                        if then_live {
+                            incoming_vals.push(then_val);
+                            incoming_blocks.push(then_end_bb);
+                        }
+                        if else_live {
+                            incoming_vals.push(else_val);
+                            incoming_blocks.push(else_end_bb);
+                        }
+                        let phi = builder.build_phi(llvm_type(ty), "iftmp");
+                        builder.add_incoming(phi, &mut incoming_vals, &mut incoming_blocks);
+                        Ok(phi)
+                    }
+                }
+            }
+            NewArray(_, _, ty, _) => Ok(llvm::get_undef(llvm_type(ty))),
+            Index(ref array, ref index, _, _) => {
+                let array_ptr = try!(array.build(module, func, entry, builder, env.clone()));
+                let index_val = try!(index.build(module, func, entry, builder, env));
+                let zero = llvm::const_int(llvm_type(Type::I32), 0);
+                let elem_ptr = builder.build_gep(array_ptr, &mut [zero, index_val], "idx");
+                Ok(builder.build_load(elem_ptr, "elem"))
+            }
         }
     }
 
 }
 
-impl<'a> FuncsDecl<'a> for Statement<'a> {
-    fn find_funcs(self: &'a Statement<'a>) -> Result<HashSet<&'a FunctionCall<'a>>, String> {
+impl<'a> FuncsDecl<'a> for Statement<'a, Type> {
+    fn find_funcs(self: &'a Statement<'a, Type>) -> Result<HashSet<&'a FunctionCall<'a>>, Diagnostic> {
         use ast::Statement::*;
         match *self {
             TermSemicolon(ref term) => term.find_funcs(),
-            Let(_, ref rhs) => rhs.find_funcs(),
-            LetMut(_, ref rhs) => rhs.find_funcs(),
-            Mutate(_, ref rhs) => rhs.find_funcs(),
+            Let(_, ref rhs, _) => rhs.find_funcs(),
+            LetMut(_, ref rhs, _) => rhs.find_funcs(),
+            Mutate(_, ref rhs, _) => rhs.find_funcs(),
+            MutateIndex(_, ref index, ref rhs, _) => {
+                Ok(try!(index.find_funcs()).union(&try!(rhs.find_funcs())).cloned().collect())
+            }
         }
     }
 }
 
-impl<'a> FuncsDecl<'a> for Block<'a> {
-    fn find_funcs(self: &'a Block<'a>) -> Result<HashSet<&'a FunctionCall<'a>>, String> {
+impl<'a> FuncsDecl<'a> for Block<'a, Type> {
+    fn find_funcs(self: &'a Block<'a, Type>) -> Result<HashSet<&'a FunctionCall<'a>>, Diagnostic> {
         let mut funcs = HashSet::new();
         for stmt in self.stmts {
             funcs = funcs.union(&try!(stmt.find_funcs())).cloned().collect()
@@ -352,18 +490,18 @@ impl<'a> FuncsDecl<'a> for Block<'a> {
     }
 }
 
-impl<'a> Compile<'a> for Block<'a> {
+impl<'a> Compile<'a> for Block<'a, Type> {
 
     type Env = Box<Map<'a>>;
 
     fn new_env() ->  Self::Env { Box::new(Map::new()) }
 
-    unsafe fn build(self: &'a Block<'a>,
-                    module: LLVMModuleRef,
-                    func: LLVMValueRef,
-                    entry: LLVMBasicBlockRef,
-                    builder: LLVMBuilderRef,
-                    mut env: Self::Env) -> Result<LLVMValueRef, Vec<String>> {
+    fn build(self: &'a Block<'a, Type>,
+             module: &mut Module,
+             func: LLVMValueRef,
+             entry: LLVMBasicBlockRef,
+             builder: &mut Builder,
+             mut env: Self::Env) -> Result<LLVMValueRef, Vec<Diagnostic>> {
         use self::Levity::*;
         use ast::Statement::*;
         for stmt in self.stmts {
@@ -371,32 +509,68 @@ impl<'a> Compile<'a> for Block<'a> {
                 TermSemicolon(ref term) => {
                     try!(term.build(module, func, entry, builder, *env.clone()));
                 }
-                Let(ref lhs, ref rhs) => {
+                Let(ref lhs, ref rhs, _) => {
+                    // `rhs`'s type is already resolved by the inference
+                    // pass (`ast::infer`), so -- unlike the old
+                    // constant-folding assumption here -- it no longer
+                    // needs to be a compile-time constant.
                     let value = try!(rhs.build(module, func, entry, builder, *env.clone()));
-                    let content_val = LLVMConstIntGetSExtValue(value) as i32;
-                    env.insert(lhs, (value, Unboxed(content_val)));
+                    let value = match rhs.ty() {
+                        // An array needs an address for `Index` to
+                        // `build_gep` into, same as a `LetMut` one --
+                        // unlike a scalar it can't live bare in an SSA
+                        // register. Still tagged `Unboxed` so `Mutate`
+                        // and `MutateIndex` keep rejecting writes to it.
+                        Type::Array(_, _) => {
+                            let alloca = builder.build_alloca(llvm_type(rhs.ty()), lhs);
+                            builder.build_store(value, alloca);
+                            alloca
+                        }
+                        _ => value,
+                    };
+                    env.insert(lhs, (value, Unboxed));
                 }
-                LetMut(ref lhs, ref rhs) => {
-                    let alloca =
-                        LLVMBuildAlloca(builder, LLVMInt32Type(), lhs.as_ptr() as *const i8);
+                LetMut(ref lhs, ref rhs, _) => {
+                    let alloca = builder.build_alloca(llvm_type(rhs.ty()), lhs);
                     let built_rhs = try!(rhs.build(module, func, entry, builder, *env.clone()));
-                    LLVMBuildStore(builder, built_rhs, alloca);
+                    builder.build_store(built_rhs, alloca);
                     env.insert(lhs, (alloca, Boxed));
                 }
-                Mutate(ref lhs, ref rhs) => {
+                Mutate(ref lhs, ref rhs, span) => {
                     let var_result = match env.get(lhs) {
                         Some(var) => Ok(*var),
-                        None => Err(
-                            vec![String::from("Variable ") + lhs + " isn't declared yet."]
-                        ),
+                        None => Err(vec![Diagnostic::error(span,
+                            String::from("Variable ") + lhs + " isn't declared yet.")]),
                     };
                     let built_rhs = try!(rhs.build(module, func, entry, builder, *env.clone()));
                     let pair = try!(var_result);
                     match pair.1 {
-                        Boxed => { LLVMBuildStore(builder, built_rhs, pair.0); }
-                        Unboxed(_) =>
-                            return Err(vec![String::from("Variable ") +
-                                            lhs + " is immutable, so it cannot be mutated."]),
+                        Boxed => { builder.build_store(built_rhs, pair.0); }
+                        Unboxed =>
+                            return Err(vec![Diagnostic::error(span,
+                                String::from("Variable ") + lhs +
+                                " is immutable, so it cannot be mutated.")]),
+                    }
+                }
+                MutateIndex(ref lhs, ref index, ref rhs, span) => {
+                    let var_result = match env.get(lhs) {
+                        Some(var) => Ok(*var),
+                        None => Err(vec![Diagnostic::error(span,
+                            String::from("Variable ") + lhs + " isn't declared yet.")]),
+                    };
+                    let index_val = try!(index.build(module, func, entry, builder, *env.clone()));
+                    let built_rhs = try!(rhs.build(module, func, entry, builder, *env.clone()));
+                    let pair = try!(var_result);
+                    match pair.1 {
+                        Boxed => {
+                            let zero = llvm::const_int(llvm_type(Type::I32), 0);
+                            let elem_ptr = builder.build_gep(pair.0, &mut [zero, index_val], "idx");
+                            builder.build_store(built_rhs, elem_ptr);
+                        }
+                        Unboxed =>
+                            return Err(vec![Diagnostic::error(span,
+                                String::from("Variable ") + lhs +
+                                " is immutable, so its elements cannot be mutated.")]),
                     }
                 }
             }
@@ -404,33 +578,3 @@ impl<'a> Compile<'a> for Block<'a> {
         self.end.build(module, func, entry, builder, *env)
     }
 }
-
-// Doesn't work right now. Will try to fix.
-pub unsafe fn emit_obj(module: LLVMModuleRef) {
-    use llvm_sys::target::*;
-    use llvm_sys::target_machine::*;
-    let triple = LLVMGetDefaultTargetTriple();
-    LLVM_InitializeNativeTarget();
-    let target = LLVMGetFirstTarget();
-    let cpu = "x86-64\0".as_ptr() as *const i8;
-    let feature = "\0".as_ptr() as *const i8;
-    let opt_level = LLVMCodeGenOptLevel::LLVMCodeGenLevelNone;
-    let reloc_mode = LLVMRelocMode::LLVMRelocDefault;
-    let code_model = LLVMCodeModel::LLVMCodeModelDefault;
-    let target_machine =
-        LLVMCreateTargetMachine(target, triple, cpu, feature, opt_level, reloc_mode, code_model);
-    let file_type = LLVMCodeGenFileType::LLVMObjectFile;
-    // TODO: error handling here.
-    LLVMTargetMachineEmitToFile(target_machine,
-                                module,
-                                "/Users/andyshiue/Desktop/main.o".to_raw().unwrap() as *mut i8,
-                                file_type,
-                                ["Cannot init_module file.\0".as_ptr()] // This is wrong.
-                                    .as_mut_ptr() as *mut *mut i8);
-}
-
-
-pub unsafe fn emit_ir(module: LLVMModuleRef) {
-    use llvm_sys::bit_writer::*;
-    LLVMWriteBitcodeToFile(module, "/Users/andyshiue/Desktop/main.bc".to_raw().unwrap());
-}
@@ -0,0 +1,153 @@
+//! Object file, bitcode and executable emission.
+//!
+//! The previous `compile::emit_obj` hardcoded
+//! `/Users/andyshiue/Desktop/main.o`, admitted in a comment that it
+//! "doesn't work right now," and passed
+//! `LLVMTargetMachineEmitToFile` an error-message array of the wrong
+//! shape (the function wants a single out-pointer, not an array of one
+//! string). This module takes a real output `Path` and a target
+//! config, checks that return code properly, and adds `link_executable`
+//! to go all the way to a runnable binary.
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::ptr;
+
+use llvm_sys::bit_writer::LLVMWriteBitcodeToFile;
+use llvm_sys::prelude::LLVMModuleRef;
+use llvm_sys::target::*;
+use llvm_sys::target_machine::*;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OptLevel {
+    None,
+    Less,
+    Default,
+    Aggressive,
+}
+
+impl OptLevel {
+    fn to_llvm(self) -> LLVMCodeGenOptLevel {
+        match self {
+            OptLevel::None => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+            OptLevel::Less => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+            OptLevel::Default => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+            OptLevel::Aggressive => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+        }
+    }
+}
+
+/// Target-machine configuration for object emission. `triple` defaults
+/// to the host triple when left `None`.
+#[derive(Clone, Debug)]
+pub struct TargetConfig {
+    pub triple: Option<String>,
+    pub cpu: String,
+    pub opt_level: OptLevel,
+}
+
+impl Default for TargetConfig {
+    fn default() -> TargetConfig {
+        TargetConfig {
+            triple: None,
+            cpu: String::from("generic"),
+            opt_level: OptLevel::None,
+        }
+    }
+}
+
+impl TargetConfig {
+    unsafe fn create_target_machine(&self) -> Result<LLVMTargetMachineRef, String> {
+        LLVM_InitializeNativeTarget();
+        LLVM_InitializeNativeAsmPrinter();
+
+        let triple = match self.triple {
+            Some(ref triple) => CString::new(triple.as_str()).unwrap(),
+            None => {
+                let default = LLVMGetDefaultTargetTriple();
+                let owned = CString::new(
+                    ::std::ffi::CStr::from_ptr(default).to_string_lossy().into_owned()
+                ).unwrap();
+                LLVMDisposeMessage(default);
+                owned
+            }
+        };
+
+        let mut target = ptr::null_mut();
+        let mut err: *mut c_char = ptr::null_mut();
+        if LLVMGetTargetFromTriple(triple.as_ptr(), &mut target, &mut err) != 0 {
+            return Err(message_from(err, "couldn't find a target for this triple"));
+        }
+
+        let cpu = CString::new(self.cpu.as_str()).unwrap();
+        let features = CString::new("").unwrap();
+        Ok(LLVMCreateTargetMachine(target,
+                                    triple.as_ptr(),
+                                    cpu.as_ptr(),
+                                    features.as_ptr(),
+                                    self.opt_level.to_llvm(),
+                                    LLVMRelocMode::LLVMRelocDefault,
+                                    LLVMCodeModel::LLVMCodeModelDefault))
+    }
+}
+
+/// Turns an llvm-sys out-pointer error message into an owned `String`,
+/// falling back to `default` if llvm-sys didn't actually set one.
+unsafe fn message_from(raw: *mut c_char, default: &str) -> String {
+    if raw.is_null() {
+        return String::from(default);
+    }
+    let message = ::std::ffi::CStr::from_ptr(raw).to_string_lossy().into_owned();
+    LLVMDisposeMessage(raw);
+    message
+}
+
+/// Emits `module` as an object file at `path`.
+pub fn emit_obj(module: LLVMModuleRef, path: &Path, config: &TargetConfig) -> Result<(), String> {
+    unsafe {
+        let target_machine = try!(config.create_target_machine());
+        let path_cstr = CString::new(path.to_str().expect("path must be valid UTF-8")).unwrap();
+        let mut err: *mut c_char = ptr::null_mut();
+        let failed = LLVMTargetMachineEmitToFile(target_machine,
+                                                  module,
+                                                  path_cstr.as_ptr() as *mut c_char,
+                                                  LLVMCodeGenFileType::LLVMObjectFile,
+                                                  &mut err);
+        LLVMDisposeTargetMachine(target_machine);
+        if failed != 0 {
+            return Err(message_from(err, "failed to emit object file"));
+        }
+        Ok(())
+    }
+}
+
+/// Writes `module`'s bitcode to `path`.
+pub fn emit_ir(module: LLVMModuleRef, path: &Path) -> Result<(), String> {
+    let path_cstr = CString::new(path.to_str().expect("path must be valid UTF-8")).unwrap();
+    let failed = unsafe { LLVMWriteBitcodeToFile(module, path_cstr.as_ptr()) };
+    if failed != 0 {
+        return Err(format!("failed to write bitcode to {}", path.display()));
+    }
+    Ok(())
+}
+
+/// Emits `module` to an object file alongside `output`, then shells out
+/// to the system linker (`cc`) to produce the `output` executable.
+pub fn link_executable(module: LLVMModuleRef,
+                        output: &Path,
+                        config: &TargetConfig) -> Result<(), String> {
+    let obj_path: PathBuf = output.with_extension("o");
+    try!(emit_obj(module, &obj_path, config));
+    let status = try!(Command::new("cc")
+        .arg(&obj_path)
+        .arg("-o")
+        .arg(output)
+        .status()
+        .map_err(|err| format!("couldn't run the system linker: {}", err)));
+    if !status.success() {
+        return Err(format!("the linker exited with {}", status));
+    }
+    Ok(())
+}
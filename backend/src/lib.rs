@@ -0,0 +1,6 @@
+extern crate llvm_sys;
+extern crate ast;
+
+pub mod llvm;
+pub mod compile;
+pub mod emit;
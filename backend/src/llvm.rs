@@ -0,0 +1,234 @@
+//! A thin safe wrapper around the slice of the llvm-sys FFI this crate
+//! needs.
+//!
+//! The previous `compile`-module-is-all-`unsafe!` approach leaned on
+//! `ToRaw::to_raw`, which handed out a `*const c_char` into a `CString`
+//! that was dropped on the same line — a dangling pointer every time it
+//! was used. `Names` below fixes that by interning each name's
+//! `CString` for the lifetime of the owning `Module`/`Builder`, so a
+//! pointer handed to llvm-sys stays valid for as long as llvm-sys might
+//! read it.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::raw::c_char;
+
+use llvm_sys::core::*;
+use llvm_sys::prelude::*;
+use llvm_sys::{LLVMIntPredicate, LLVMTypeKind};
+
+/// Interns `&str` names as `CString`s so the same literal (`"add"`,
+/// `"iszero"`, a variable name reused across phi nodes, ...) is only
+/// allocated once, and the pointer handed to llvm-sys always points at
+/// a `CString` `self` still owns.
+struct Names {
+    interned: HashMap<String, CString>,
+}
+
+impl Names {
+    fn new() -> Names {
+        Names { interned: HashMap::new() }
+    }
+
+    fn raw(&mut self, name: &str) -> *const c_char {
+        if !self.interned.contains_key(name) {
+            let cstring = CString::new(name).expect("LLVM name must not contain a NUL byte");
+            self.interned.insert(name.to_string(), cstring);
+        }
+        self.interned[name].as_ptr()
+    }
+}
+
+/// Creates a module in the global LLVM context -- the same context
+/// `llvm_type`'s `LLVMInt32Type`/`LLVMInt1Type`/`LLVMVoidType` and
+/// `Builder::new`'s `LLVMCreateBuilder` implicitly use. A module needs
+/// its types, constants and builder to all come from one context, and
+/// since nothing here threads an explicit `LLVMContextRef` through
+/// codegen, the global one is the only context in play.
+pub fn create_module(name: &str) -> Module {
+    let cname = CString::new(name).expect("module name must not contain a NUL byte");
+    Module {
+        raw: unsafe { LLVMModuleCreateWithName(cname.as_ptr()) },
+        names: Names::new(),
+    }
+}
+
+pub struct Module {
+    raw: LLVMModuleRef,
+    names: Names,
+}
+
+impl Module {
+    pub fn as_raw(&self) -> LLVMModuleRef {
+        self.raw
+    }
+
+    pub fn add_function(&mut self, name: &str, func_ty: LLVMTypeRef) -> LLVMValueRef {
+        let raw_name = self.names.raw(name);
+        unsafe { LLVMAddFunction(self.raw, raw_name, func_ty) }
+    }
+
+    pub fn get_named_function(&mut self, name: &str) -> LLVMValueRef {
+        let raw_name = self.names.raw(name);
+        unsafe { LLVMGetNamedFunction(self.raw, raw_name) }
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeModule(self.raw) }
+    }
+}
+
+pub struct Builder {
+    raw: LLVMBuilderRef,
+    names: Names,
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder { raw: unsafe { LLVMCreateBuilder() }, names: Names::new() }
+    }
+
+    pub fn append_basic_block(&mut self, func: LLVMValueRef, name: &str) -> LLVMBasicBlockRef {
+        let raw_name = self.names.raw(name);
+        unsafe { LLVMAppendBasicBlock(func, raw_name) }
+    }
+
+    pub fn position_at_end(&mut self, block: LLVMBasicBlockRef) {
+        unsafe { LLVMPositionBuilderAtEnd(self.raw, block) }
+    }
+
+    pub fn insert_block(&mut self) -> LLVMBasicBlockRef {
+        unsafe { LLVMGetInsertBlock(self.raw) }
+    }
+
+    pub fn build_add(&mut self, left: LLVMValueRef, right: LLVMValueRef, name: &str) -> LLVMValueRef {
+        let raw_name = self.names.raw(name);
+        unsafe { LLVMBuildAdd(self.raw, left, right, raw_name) }
+    }
+
+    pub fn build_sub(&mut self, left: LLVMValueRef, right: LLVMValueRef, name: &str) -> LLVMValueRef {
+        let raw_name = self.names.raw(name);
+        unsafe { LLVMBuildSub(self.raw, left, right, raw_name) }
+    }
+
+    pub fn build_mul(&mut self, left: LLVMValueRef, right: LLVMValueRef, name: &str) -> LLVMValueRef {
+        let raw_name = self.names.raw(name);
+        unsafe { LLVMBuildMul(self.raw, left, right, raw_name) }
+    }
+
+    pub fn build_sdiv(&mut self, left: LLVMValueRef, right: LLVMValueRef, name: &str) -> LLVMValueRef {
+        let raw_name = self.names.raw(name);
+        unsafe { LLVMBuildSDiv(self.raw, left, right, raw_name) }
+    }
+
+    pub fn build_icmp(&mut self,
+                       pred: LLVMIntPredicate,
+                       left: LLVMValueRef,
+                       right: LLVMValueRef,
+                       name: &str) -> LLVMValueRef {
+        let raw_name = self.names.raw(name);
+        unsafe { LLVMBuildICmp(self.raw, pred, left, right, raw_name) }
+    }
+
+    pub fn build_cond_br(&mut self,
+                          cond: LLVMValueRef,
+                          then_block: LLVMBasicBlockRef,
+                          else_block: LLVMBasicBlockRef) -> LLVMValueRef {
+        unsafe { LLVMBuildCondBr(self.raw, cond, then_block, else_block) }
+    }
+
+    pub fn build_br(&mut self, dest: LLVMBasicBlockRef) -> LLVMValueRef {
+        unsafe { LLVMBuildBr(self.raw, dest) }
+    }
+
+    pub fn build_phi(&mut self, ty: LLVMTypeRef, name: &str) -> LLVMValueRef {
+        let raw_name = self.names.raw(name);
+        unsafe { LLVMBuildPhi(self.raw, ty, raw_name) }
+    }
+
+    pub fn add_incoming(&mut self,
+                         phi: LLVMValueRef,
+                         incoming: &mut [LLVMValueRef],
+                         blocks: &mut [LLVMBasicBlockRef]) {
+        assert_eq!(incoming.len(), blocks.len());
+        unsafe {
+            LLVMAddIncoming(phi, incoming.as_mut_ptr(), blocks.as_mut_ptr(), incoming.len() as u32)
+        }
+    }
+
+    pub fn build_call(&mut self,
+                       func: LLVMValueRef,
+                       args: &mut [LLVMValueRef],
+                       name: &str) -> LLVMValueRef {
+        let raw_name = self.names.raw(name);
+        unsafe { LLVMBuildCall(self.raw, func, args.as_mut_ptr(), args.len() as u32, raw_name) }
+    }
+
+    pub fn build_load(&mut self, ptr: LLVMValueRef, name: &str) -> LLVMValueRef {
+        let raw_name = self.names.raw(name);
+        unsafe { LLVMBuildLoad(self.raw, ptr, raw_name) }
+    }
+
+    pub fn build_store(&mut self, value: LLVMValueRef, ptr: LLVMValueRef) -> LLVMValueRef {
+        unsafe { LLVMBuildStore(self.raw, value, ptr) }
+    }
+
+    pub fn build_alloca(&mut self, ty: LLVMTypeRef, name: &str) -> LLVMValueRef {
+        let raw_name = self.names.raw(name);
+        unsafe { LLVMBuildAlloca(self.raw, ty, raw_name) }
+    }
+
+    /// Computes the address of `indices` into `ptr`, e.g. `&ptr[0][i]`
+    /// for an array element (the leading `0` steps through the pointer
+    /// itself, matching how LLVM indexes into an alloca'd array type).
+    pub fn build_gep(&mut self,
+                      ptr: LLVMValueRef,
+                      indices: &mut [LLVMValueRef],
+                      name: &str) -> LLVMValueRef {
+        let raw_name = self.names.raw(name);
+        unsafe {
+            LLVMBuildGEP(self.raw, ptr, indices.as_mut_ptr(), indices.len() as u32, raw_name)
+        }
+    }
+
+    pub fn build_ret(&mut self, value: LLVMValueRef) -> LLVMValueRef {
+        unsafe { LLVMBuildRet(self.raw, value) }
+    }
+
+    /// Whether `block` already ends in a terminator instruction (a
+    /// `ret`/`br`/...). Used to tell a branch that fell off the end of
+    /// an `if`/`else` arm (which still needs a `br` to the merge block
+    /// and a phi incoming edge) from one that already terminated itself
+    /// (which needs neither, and would make an already-terminated
+    /// block invalid IR if given another).
+    pub fn block_terminated(&self, block: LLVMBasicBlockRef) -> bool {
+        unsafe { !LLVMGetBasicBlockTerminator(block).is_null() }
+    }
+}
+
+impl Drop for Builder {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeBuilder(self.raw) }
+    }
+}
+
+/// A constant integer of `ty`, which must be an integer type.
+pub fn const_int(ty: LLVMTypeRef, value: u64) -> LLVMValueRef {
+    debug_assert_eq!(unsafe { LLVMGetTypeKind(ty) }, LLVMTypeKind::LLVMIntegerTypeKind);
+    unsafe { LLVMConstInt(ty, value, 0) }
+}
+
+/// The `index`th parameter of `func`, for binding a `FunctionDef`'s
+/// parameters into its body's environment.
+pub fn get_param(func: LLVMValueRef, index: u32) -> LLVMValueRef {
+    unsafe { LLVMGetParam(func, index) }
+}
+
+/// An `undef` value of `ty`, used as the initial value of a freshly
+/// allocated array; an element read before it is written through an
+/// indexed `Mutate` yields `undef`.
+pub fn get_undef(ty: LLVMTypeRef) -> LLVMValueRef {
+    unsafe { LLVMGetUndef(ty) }
+}